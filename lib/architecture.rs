@@ -0,0 +1,46 @@
+//! The instruction set architecture a loaded binary was compiled for.
+
+use std::fmt;
+
+
+/// The machine architecture reported by a `Loader`.
+///
+/// This is deliberately coarse-grained: it exists so lifters and other
+/// downstream consumers can pick the right register set and word size,
+/// not to model every CPU variant goblin understands.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Architecture {
+    X86,
+    X8664,
+    Arm,
+    Arm64,
+    Mips
+}
+
+
+impl Architecture {
+    /// The native word size of this architecture, in bits.
+    pub fn bits(&self) -> usize {
+        match *self {
+            Architecture::X86 => 32,
+            Architecture::X8664 => 64,
+            Architecture::Arm => 32,
+            Architecture::Arm64 => 64,
+            Architecture::Mips => 32
+        }
+    }
+}
+
+
+impl fmt::Display for Architecture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match *self {
+            Architecture::X86 => "x86",
+            Architecture::X8664 => "x86-64",
+            Architecture::Arm => "arm",
+            Architecture::Arm64 => "arm64",
+            Architecture::Mips => "mips"
+        };
+        write!(f, "{}", s)
+    }
+}
@@ -7,12 +7,14 @@
 //!
 //! To create a `Block`, call `ControlFlowGraph::new_block`.
 
+use std::collections::BTreeMap;
+use std::cmp::Ordering;
 use std::fmt;
 use il::*;
 
 
 /// A basic block in Falcon IL.
-#[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Block {
     /// The index of the block.
     index: u64,
@@ -22,6 +24,11 @@ pub struct Block {
     next_temp_index: u64,
     /// The instructions for this block.
     instructions: Vec<Instruction>,
+    /// A side index from instruction index to its position in
+    /// `instructions`, kept in sync by every method that adds or removes an
+    /// instruction. This turns `instruction`/`instruction_mut`/
+    /// `remove_instruction` from an O(n) scan into an O(log n) lookup.
+    instruction_indices: BTreeMap<u64, usize>,
 }
 
 
@@ -31,7 +38,8 @@ impl Block {
             index: index,
             next_instruction_index: 0,
             next_temp_index: 0,
-            instructions: Vec::new()
+            instructions: Vec::new(),
+            instruction_indices: BTreeMap::new()
         }
     }
 
@@ -44,21 +52,68 @@ impl Block {
 
 
     fn push(&mut self, instruction: Instruction) {
+        self.instruction_indices.insert(instruction.index(), self.instructions.len());
         self.instructions.push(instruction);
     }
 
 
+    /// Insert `instruction` at position `vec_index` in `instructions`,
+    /// shifting every instruction at or after that position up by one and
+    /// updating `instruction_indices` to match.
+    fn insert_at(&mut self, vec_index: usize, instruction: Instruction) {
+        for position in self.instruction_indices.values_mut() {
+            if *position >= vec_index {
+                *position += 1;
+            }
+        }
+        self.instruction_indices.insert(instruction.index(), vec_index);
+        self.instructions.insert(vec_index, instruction);
+    }
+
+
     /// Appends the contents of another `Block` to this `Block`.
     ///
     /// Instruction indices are updated accordingly.
     pub fn append(&mut self, other: &Block) {
         for instruction in other.instructions().iter() {
             let instruction = instruction.clone_new_index(self.new_instruction_index());
-            self.instructions.push(instruction);
+            self.push(instruction);
         }
     }
 
 
+    /// Insert a new instruction, built from a freshly-allocated instruction
+    /// index, immediately before the instruction at `index`.
+    ///
+    /// Returns the index assigned to the newly-inserted instruction.
+    pub fn insert_before<F>(&mut self, index: u64, build: F) -> Result<u64>
+        where F: FnOnce(u64) -> Instruction
+    {
+        let vec_index = *self.instruction_indices
+            .get(&index)
+            .ok_or(format!("No instruction with index {} found", index))?;
+        let new_index = self.new_instruction_index();
+        self.insert_at(vec_index, build(new_index));
+        Ok(new_index)
+    }
+
+
+    /// Insert a new instruction, built from a freshly-allocated instruction
+    /// index, immediately after the instruction at `index`.
+    ///
+    /// Returns the index assigned to the newly-inserted instruction.
+    pub fn insert_after<F>(&mut self, index: u64, build: F) -> Result<u64>
+        where F: FnOnce(u64) -> Instruction
+    {
+        let vec_index = *self.instruction_indices
+            .get(&index)
+            .ok_or(format!("No instruction with index {} found", index))?;
+        let new_index = self.new_instruction_index();
+        self.insert_at(vec_index + 1, build(new_index));
+        Ok(new_index)
+    }
+
+
     /// Returns the index of this `Block`
     pub fn index(&self) -> u64 {
         self.index
@@ -80,43 +135,40 @@ impl Block {
     /// Returns an `Instruction` by index, or `None` if the instruction does not
     /// exist.
     pub fn instruction(&self, index: u64) -> Option<&Instruction> {
-        for instruction in &self.instructions {
-            if instruction.index() == index {
-                return Some(instruction);
-            }
+        match self.instruction_indices.get(&index) {
+            Some(&vec_index) => self.instructions.get(vec_index),
+            None => None
         }
-        None
     }
 
 
     /// Returns a mutable reference to an `Instruction` by index, or `None` if
     /// the `Instruction` does not exist.
     pub fn instruction_mut<>(&mut self, index: u64) -> Option<&mut Instruction> {
-        for i in 0..self.instructions.len() {
-            if self.instructions[i].index() == index {
-                return Some(&mut self.instructions[i]);
-            }
-        }
-        None
+        let vec_index = match self.instruction_indices.get(&index) {
+            Some(&vec_index) => vec_index,
+            None => return None
+        };
+        self.instructions.get_mut(vec_index)
     }
 
 
     /// Deletes an `Instruction` by its index.
     pub fn remove_instruction(&mut self, index: u64) -> Result<()> {
-        let mut vec_index = None;
-        for i in 0..self.instructions.len() {
-            if self.instructions[i].index() == index {
-                vec_index = Some(i);
-                break;
+        let vec_index = match self.instruction_indices.remove(&index) {
+            Some(vec_index) => vec_index,
+            None => return Err(format!("No instruction with index {} found", index).into())
+        };
+
+        self.instructions.remove(vec_index);
+
+        for position in self.instruction_indices.values_mut() {
+            if *position > vec_index {
+                *position -= 1;
             }
         }
-        match vec_index {
-            Some(index) => {
-                self.instructions.remove(index);
-                Ok(())
-            },
-            None => Err(format!("No instruction with index {} found", index).into()),
-        }
+
+        Ok(())
     }
 
 
@@ -128,6 +180,33 @@ impl Block {
     }
 
 
+    /// Insert an `Instruction` that already carries its final index (for
+    /// example, one parsed back out of this block's own `Display` text),
+    /// rather than allocating a fresh one from `new_instruction_index`.
+    ///
+    /// `next_instruction_index` is advanced if required so indices assigned
+    /// after this call still come out unique.
+    pub(crate) fn insert_parsed_instruction(&mut self, instruction: Instruction) {
+        let index = instruction.index();
+        if index >= self.next_instruction_index {
+            self.next_instruction_index = index + 1;
+        }
+        self.push(instruction);
+    }
+
+
+    /// Record that `temp_index` is already in use by a temporary named via
+    /// `temp`, advancing `next_temp_index` past it if required.
+    ///
+    /// Used when reconstructing a `Block` from text, where temporaries show
+    /// up embedded in scalar names rather than through calls to `temp`.
+    pub(crate) fn observe_temp_index(&mut self, temp_index: u64) {
+        if temp_index >= self.next_temp_index {
+            self.next_temp_index = temp_index + 1;
+        }
+    }
+
+
     /// Generates a temporary scalar unique to this block.
     pub fn temp(&mut self, bits: usize) -> Scalar {
         let next_index = self.next_temp_index;
@@ -175,7 +254,34 @@ impl Block {
     pub fn prepend_phi(&mut self, dst: MultiVar, src: Vec<MultiVar>) {
         let index = self.new_instruction_index();
         let phi = Instruction::phi(index, dst, src);
-        self.instructions.insert(0, phi);
+        self.insert_at(0, phi);
+    }
+}
+
+
+impl PartialEq for Block {
+    fn eq(&self, other: &Block) -> bool {
+        self.index == other.index
+            && self.next_instruction_index == other.next_instruction_index
+            && self.next_temp_index == other.next_temp_index
+            && self.instructions == other.instructions
+    }
+}
+
+impl Eq for Block {}
+
+impl PartialOrd for Block {
+    fn partial_cmp(&self, other: &Block) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Block {
+    fn cmp(&self, other: &Block) -> Ordering {
+        self.index.cmp(&other.index)
+            .then_with(|| self.next_instruction_index.cmp(&other.next_instruction_index))
+            .then_with(|| self.next_temp_index.cmp(&other.next_temp_index))
+            .then_with(|| self.instructions.cmp(&other.instructions))
     }
 }
 
@@ -0,0 +1,501 @@
+//! Parses the text produced by `Block`/`ControlFlowGraph`'s `Display` impls
+//! back into their in-memory forms.
+//!
+//! This is the inverse of `Block`'s `Display` impl: given a dump of IL such
+//! as
+//!
+//! ```text
+//! [ Block: 0x0 ]
+//! 0: temp_0.0:32 = (input_0:32 + 0x1:32)
+//! 1: store(memory, temp_0.0:32, 0x0:32)
+//! 2: brc 0x1000:32 ? temp_0.0:32
+//! ```
+//!
+//! `parse_block` rebuilds a `Block` with the same instructions, with real
+//! instruction indices parsed from the `N:` prefix (not re-derived),
+//! `next_instruction_index` set to one past the highest index in the listing
+//! and `next_temp_index` set to one past the highest `temp_<block>.<n>`
+//! temporary it names. A counter that had moved past everything the listing
+//! shows (an instruction removed from the end, a temporary never used) is
+//! not recovered. `parse_control_flow_graph` (aliased as
+//! `parse`) does the same for a whole graph: one block listing per block,
+//! followed by an `edges:` section of `head -> tail` (and, for conditional
+//! edges, `head -> tail [condition]`) lines.
+//!
+//! This lets lifter/pass tests hand-write fixtures as text instead of
+//! chaining `Block`/`ControlFlowGraph` method calls, and lets regression
+//! tests assert `parse(&format!("{}", cfg)) == cfg`.
+
+use error::*;
+use il::*;
+
+
+/// Parse a full `ControlFlowGraph` dump (as produced by its `Display` impl):
+/// one block listing per block, followed by an `edges:` section.
+pub fn parse(text: &str) -> Result<ControlFlowGraph> {
+    parse_control_flow_graph(text)
+}
+
+
+/// Parse a full `ControlFlowGraph` dump (as produced by its `Display` impl):
+/// one block listing per block, followed by an `edges:` section.
+pub fn parse_control_flow_graph(text: &str) -> Result<ControlFlowGraph> {
+    let (blocks_text, edges_text) = match text.find("\nedges:") {
+        Some(i) => (&text[..i], &text[(i + "\nedges:".len())..]),
+        None => (text, "")
+    };
+
+    let mut cfg = ControlFlowGraph::new();
+
+    for block_text in split_blocks(blocks_text) {
+        let block = parse_block(block_text)?;
+        cfg.insert_block(block)?;
+    }
+
+    for line in edges_text.lines().map(|line| line.trim()).filter(|line| !line.is_empty()) {
+        parse_edge_line(&mut cfg, line)?;
+    }
+
+    Ok(cfg)
+}
+
+
+/// Split the text of multiple concatenated `[ Block: 0xN ]` listings back
+/// into one slice per block.
+fn split_blocks(text: &str) -> Vec<&str> {
+    let mut blocks = Vec::new();
+    let mut start = None;
+
+    for (i, _) in text.match_indices("[ Block: ") {
+        if let Some(s) = start {
+            blocks.push(text[s..i].trim());
+        }
+        start = Some(i);
+    }
+    if let Some(s) = start {
+        blocks.push(text[s..].trim());
+    }
+
+    blocks
+}
+
+
+/// Parse one `head -> tail` or `head -> tail [condition]` edge line and add
+/// it to `cfg`.
+fn parse_edge_line(cfg: &mut ControlFlowGraph, line: &str) -> Result<()> {
+    let (head, rest) = split_once(line, '>')
+        .ok_or_else(|| format!("Malformed edge: {}", line))?;
+    let head = parse_u64(head.trim_right_matches('-').trim())?;
+
+    match split_once(rest, '[') {
+        Some((tail, condition)) => {
+            let tail = parse_u64(tail.trim())?;
+            let condition = condition.trim().trim_right_matches(']').trim();
+            cfg.conditional_edge(head, tail, parse_expression(condition)?)?;
+        },
+        None => {
+            let tail = parse_u64(rest.trim())?;
+            cfg.unconditional_edge(head, tail)?;
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Parse a single `[ Block: 0xN ]` listing (as produced by `Block`'s
+/// `Display` impl) back into a `Block`.
+pub fn parse_block(text: &str) -> Result<Block> {
+    let mut lines = text.lines().map(|line| line.trim()).filter(|line| !line.is_empty());
+
+    let header = lines.next().ok_or("Empty block text")?;
+    let index = parse_block_header(header)?;
+
+    let mut block = Block::new(index);
+
+    for line in lines {
+        parse_instruction_line(&mut block, line)?;
+    }
+
+    restore_temp_index(&mut block, text);
+
+    Ok(block)
+}
+
+
+/// Parse the `[ Block: 0xN ]` header line and return the block's index.
+fn parse_block_header(line: &str) -> Result<u64> {
+    if !line.starts_with("[ Block: ") || !line.ends_with(" ]") {
+        return Err(format!("Malformed block header: {}", line).into());
+    }
+
+    let hex = &line["[ Block: ".len()..(line.len() - " ]".len())];
+    parse_u64(hex)
+}
+
+
+/// Scan `text` for every `temp_<block index>.<n>` scalar name and restore
+/// `block`'s `next_temp_index` accordingly.
+///
+/// `Block::temp` is never called while parsing (the parsed instructions
+/// already carry their temporaries' final names), so without this the
+/// counter would stay at 0 and a pass that called `block.temp(..)` on a
+/// parsed block could collide with a temporary already in use.
+fn restore_temp_index(block: &mut Block, text: &str) {
+    let prefix = format!("temp_{}.", block.index());
+
+    for line in text.lines() {
+        let mut rest = line;
+        while let Some(start) = rest.find(&prefix) {
+            let digits_start = &rest[(start + prefix.len())..];
+            let digits: String = digits_start.chars().take_while(|c| c.is_digit(10)).collect();
+
+            if let Ok(n) = digits.parse::<u64>() {
+                block.observe_temp_index(n);
+            }
+
+            rest = &digits_start[digits.len()..];
+        }
+    }
+}
+
+
+/// Parse one `index: operation` line and insert the resulting `Instruction`
+/// into `block`, preserving its real parsed index (rather than letting
+/// `Block` assign the next sequential one), so that a block with removed
+/// instructions (non-contiguous indices) round-trips correctly.
+fn parse_instruction_line(block: &mut Block, line: &str) -> Result<()> {
+    let (index, rest) = split_once(line, ':')
+        .ok_or_else(|| format!("Malformed instruction line: {}", line))?;
+    let index = parse_u64(index)?;
+    let rest = rest.trim();
+
+    if rest.starts_with("raise ") {
+        let expr = parse_expression(&rest["raise ".len()..])?;
+        block.insert_parsed_instruction(Instruction::raise(index, expr));
+        return Ok(());
+    }
+
+    if rest.starts_with("brc ") {
+        let rest = &rest["brc ".len()..];
+        let (dst, condition) = split_once(rest, '?')
+            .ok_or_else(|| format!("Malformed brc instruction: {}", line))?;
+        block.insert_parsed_instruction(Instruction::brc(
+            index,
+            parse_expression(dst.trim())?,
+            parse_expression(condition.trim())?
+        ));
+        return Ok(());
+    }
+
+    if rest.starts_with("store(") {
+        let inner = &rest["store(".len()..(rest.len() - 1)];
+        let parts = split_n(inner, ',', 3)
+            .ok_or_else(|| format!("Malformed store instruction: {}", line))?;
+        let dst = Array::new(parts[0].trim());
+        let address = parse_expression(parts[1].trim())?;
+        let src = parse_expression(parts[2].trim())?;
+        block.insert_parsed_instruction(Instruction::store(index, dst, address, src));
+        return Ok(());
+    }
+
+    // Everything else is an assignment: `dst = src`, where `src` may itself
+    // be `load(array, address)` or `phi(a, b, ...)`.
+    let (dst, src) = split_once(rest, '=')
+        .ok_or_else(|| format!("Malformed instruction: {}", line))?;
+    let dst = dst.trim();
+    let src = src.trim();
+
+    if src.starts_with("load(") {
+        let inner = &src["load(".len()..(src.len() - 1)];
+        let (array, address) = split_once(inner, ',')
+            .ok_or_else(|| format!("Malformed load instruction: {}", line))?;
+        block.insert_parsed_instruction(Instruction::load(
+            index,
+            parse_scalar(dst)?,
+            parse_expression(address.trim())?,
+            Array::new(array.trim())
+        ));
+        return Ok(());
+    }
+
+    if src.starts_with("phi(") {
+        let inner = &src["phi(".len()..(src.len() - 1)];
+        let dst = parse_multivar(dst)?;
+        let operands = inner.split(',')
+                             .map(|operand| parse_multivar(operand.trim()))
+                             .collect::<Result<Vec<MultiVar>>>()?;
+        block.insert_parsed_instruction(Instruction::phi(index, dst, operands));
+        return Ok(());
+    }
+
+    block.insert_parsed_instruction(Instruction::assign(index, parse_scalar(dst)?, parse_expression(src)?));
+
+    Ok(())
+}
+
+
+/// Split on the first occurrence of `separator`, returning `(before, after)`.
+fn split_once(s: &str, separator: char) -> Option<(&str, &str)> {
+    s.find(separator).map(|i| (&s[..i], &s[i + separator.len_utf8()..]))
+}
+
+
+/// Split `s` on `separator` into exactly `n` pieces, respecting that the
+/// last piece may itself contain `separator` (used for `store`'s three
+/// comma-separated operands, the last of which is an arbitrary expression).
+fn split_n(s: &str, separator: char, n: usize) -> Option<Vec<&str>> {
+    let mut parts = Vec::new();
+    let mut rest = s;
+    for _ in 0..(n - 1) {
+        let (part, remainder) = split_once(rest, separator)?;
+        parts.push(part);
+        rest = remainder;
+    }
+    parts.push(rest);
+    Some(parts)
+}
+
+
+fn parse_u64(s: &str) -> Result<u64> {
+    let s = s.trim();
+    if s.starts_with("0x") {
+        u64::from_str_radix(&s[2..], 16).map_err(|e| format!("{}", e).into())
+    }
+    else {
+        s.parse::<u64>().map_err(|e| format!("{}", e).into())
+    }
+}
+
+
+/// `name:bits`, e.g. `temp_0.3:32`. Note the scalar's own *name* may itself
+/// contain a `.` (as `Block::temp` names do); only the final `:bits` suffix
+/// is significant to this parser.
+fn parse_scalar(s: &str) -> Result<Scalar> {
+    let s = s.trim();
+    let colon = s.rfind(':').ok_or_else(|| format!("Malformed scalar: {}", s))?;
+    let (name, bits) = (&s[..colon], &s[(colon + 1)..]);
+    let bits = bits.parse::<usize>()
+                   .map_err(|_| format!("Malformed scalar bit width: {}", s))?;
+    Ok(Scalar::new(name, bits))
+}
+
+
+/// `name:bits`, used for `phi` operands (which are merged scalars rather
+/// than plain single-definition scalars).
+fn parse_multivar(s: &str) -> Result<MultiVar> {
+    let scalar = parse_scalar(s)?;
+    Ok(MultiVar::new(scalar))
+}
+
+
+/// An expression parser covering constants (`0x1:32`), scalars
+/// (`temp_0.3:32`), casts (`sext.64(expr)`, `zext.32(expr)`,
+/// `trun.8(expr)`), the ternary `ite(condition, then, else)`, and
+/// left-associative binary operations written `(lhs OP rhs)`.
+fn parse_expression(s: &str) -> Result<Expression> {
+    let s = s.trim();
+
+    if s.starts_with("sext.") || s.starts_with("zext.") || s.starts_with("trun.") {
+        return parse_cast_expression(s);
+    }
+
+    if s.starts_with("ite(") && s.ends_with(')') {
+        let inner = &s["ite(".len()..(s.len() - 1)];
+        let parts = split_n(inner, ',', 3)
+            .ok_or_else(|| format!("Malformed ite: {}", s))?;
+        return Ok(Expression::ite(
+            parse_expression(parts[0])?,
+            parse_expression(parts[1])?,
+            parse_expression(parts[2])?
+        )?);
+    }
+
+    if s.starts_with('(') && s.ends_with(')') {
+        return parse_binary_expression(&s[1..(s.len() - 1)]);
+    }
+
+    if let Some(colon) = s.rfind(':') {
+        let (value, bits) = (&s[..colon], &s[(colon + 1)..]);
+        if let Ok(bits) = bits.parse::<usize>() {
+            if let Ok(value) = parse_u64(value) {
+                return Ok(Expression::constant(Constant::new(value, bits)));
+            }
+        }
+    }
+
+    Ok(Expression::scalar(parse_scalar(s)?))
+}
+
+
+/// Parse `op.bits(expr)`, where `op` is `sext`, `zext` or `trun`.
+fn parse_cast_expression(s: &str) -> Result<Expression> {
+    let dot = s.find('.').ok_or_else(|| format!("Malformed cast: {}", s))?;
+    let op = &s[..dot];
+    let rest = &s[(dot + 1)..];
+
+    let paren = rest.find('(').ok_or_else(|| format!("Malformed cast: {}", s))?;
+    if !rest.ends_with(')') {
+        return Err(format!("Malformed cast: {}", s).into());
+    }
+
+    let bits = rest[..paren].parse::<usize>()
+                            .map_err(|_| format!("Malformed cast bit width: {}", s))?;
+    let inner = parse_expression(&rest[(paren + 1)..(rest.len() - 1)])?;
+
+    Ok(match op {
+        "sext" => Expression::sext(bits, inner)?,
+        "zext" => Expression::zext(bits, inner)?,
+        "trun" => Expression::trun(bits, inner)?,
+        _ => return Err(format!("Unsupported cast: {}", op).into())
+    })
+}
+
+
+/// Parse `lhs OP rhs`, where `OP` is one of Falcon IL's binary operators.
+fn parse_binary_expression(s: &str) -> Result<Expression> {
+    const OPERATORS: &'static [&'static str] = &[
+        "==", "!=", "<=", ">=", "<<", ">>",
+        "+", "-", "*", "/", "%", "&", "|", "^", "<", ">"
+    ];
+
+    for op in OPERATORS {
+        if let Some(i) = find_top_level(s, op) {
+            let lhs = parse_expression(&s[..i])?;
+            let rhs = parse_expression(&s[(i + op.len())..])?;
+            return Ok(match *op {
+                "+"  => Expression::add(lhs, rhs)?,
+                "-"  => Expression::sub(lhs, rhs)?,
+                "*"  => Expression::mul(lhs, rhs)?,
+                "/"  => Expression::divu(lhs, rhs)?,
+                "%"  => Expression::modu(lhs, rhs)?,
+                "&"  => Expression::and(lhs, rhs)?,
+                "|"  => Expression::or(lhs, rhs)?,
+                "^"  => Expression::xor(lhs, rhs)?,
+                "<<" => Expression::shl(lhs, rhs)?,
+                ">>" => Expression::shr(lhs, rhs)?,
+                "==" => Expression::cmpeq(lhs, rhs)?,
+                "!=" => Expression::cmpneq(lhs, rhs)?,
+                "<"  => Expression::cmpltu(lhs, rhs)?,
+                "<=" => Expression::cmpleu(lhs, rhs)?,
+                _    => return Err(format!("Unsupported operator: {}", op).into())
+            });
+        }
+    }
+
+    Err(format!("Malformed expression: {}", s).into())
+}
+
+
+/// Find the first top-level (not inside nested parentheses) occurrence of
+/// `needle`.
+fn find_top_level(s: &str, needle: &str) -> Option<usize> {
+    let mut depth = 0i32;
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'(' => depth += 1,
+            b')' => depth -= 1,
+            _ if depth == 0 && s[i..].starts_with(needle) => return Some(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_a_block_with_a_temp() {
+        let mut block = Block::new(0);
+        let temp = block.temp(32);
+        block.assign(temp.clone(), Expression::constant(Constant::new(1, 32)));
+        block.assign(Scalar::new("eax", 32), Expression::scalar(temp));
+
+        let text = format!("{}", block);
+        let parsed = parse_block(&text).unwrap();
+
+        assert_eq!(parsed, block);
+    }
+
+    #[test]
+    fn round_trips_a_block_with_non_contiguous_instruction_indices() {
+        let mut block = Block::new(0);
+        block.assign(Scalar::new("eax", 32), Expression::constant(Constant::new(1, 32)));
+
+        block.assign(Scalar::new("ebx", 32), Expression::constant(Constant::new(2, 32)));
+        let removed = block.instructions().last().unwrap().index();
+        block.remove_instruction(removed).unwrap();
+
+        block.assign(Scalar::new("ecx", 32), Expression::constant(Constant::new(3, 32)));
+
+        let text = format!("{}", block);
+        let parsed = parse_block(&text).unwrap();
+
+        assert_eq!(parsed, block);
+    }
+
+    #[test]
+    fn round_trips_a_block_with_every_operation_kind() {
+        let mut block = Block::new(0);
+
+        block.assign(Scalar::new("eax", 32), Expression::constant(Constant::new(1, 32)));
+        block.store(
+            Array::new("memory"),
+            Expression::constant(Constant::new(0x1000, 32)),
+            Expression::scalar(Scalar::new("eax", 32))
+        );
+        block.load(
+            Scalar::new("ebx", 32),
+            Expression::constant(Constant::new(0x1000, 32)),
+            Array::new("memory")
+        );
+        block.phi(
+            MultiVar::new(Scalar::new("ecx", 32)),
+            vec![
+                MultiVar::new(Scalar::new("eax", 32)),
+                MultiVar::new(Scalar::new("ebx", 32))
+            ]
+        );
+        block.brc(
+            Expression::constant(Constant::new(0x2000, 32)),
+            Expression::scalar(Scalar::new("ecx", 32))
+        );
+        block.raise(Expression::scalar(Scalar::new("ecx", 32)));
+
+        let text = format!("{}", block);
+        let parsed = parse_block(&text).unwrap();
+
+        assert_eq!(parsed, block);
+    }
+
+    #[test]
+    fn round_trips_a_control_flow_graph_with_conditional_edges() {
+        let mut head = Block::new(0);
+        head.assign(Scalar::new("eax", 32), Expression::constant(Constant::new(1, 32)));
+
+        let mut then_block = Block::new(1);
+        then_block.assign(Scalar::new("ebx", 32), Expression::constant(Constant::new(2, 32)));
+
+        let mut else_block = Block::new(2);
+        else_block.assign(Scalar::new("ebx", 32), Expression::constant(Constant::new(3, 32)));
+
+        let mut cfg = ControlFlowGraph::new();
+        cfg.insert_block(head).unwrap();
+        cfg.insert_block(then_block).unwrap();
+        cfg.insert_block(else_block).unwrap();
+        cfg.conditional_edge(0, 1, Expression::scalar(Scalar::new("eax", 32))).unwrap();
+        cfg.conditional_edge(0, 2, Expression::constant(Constant::new(0, 1))).unwrap();
+        cfg.unconditional_edge(1, 2).unwrap();
+
+        let text = format!("{}", cfg);
+        let parsed = parse(&text).unwrap();
+
+        assert_eq!(parsed, cfg);
+    }
+}
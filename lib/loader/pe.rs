@@ -0,0 +1,205 @@
+use error::*;
+use goblin;
+use goblin::Hint;
+use loader::*;
+use loader::memory::*;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// http://stackoverflow.com/questions/37678698/function-to-build-a-fixed-sized-array-from-slice/37679019#37679019
+use std::convert::AsMut;
+
+fn clone_into_array<A, T>(slice: &[T]) -> A
+    where A: Sized + Default + AsMut<[T]>,
+          T: Clone
+{
+    let mut a = Default::default();
+    <A as AsMut<[T]>>::as_mut(&mut a).clone_from_slice(slice);
+    a
+}
+
+
+// IMAGE_SCN_MEM_* section characteristic flags.
+const IMAGE_SCN_MEM_EXECUTE: u32 = 0x2000_0000;
+const IMAGE_SCN_MEM_READ:    u32 = 0x4000_0000;
+const IMAGE_SCN_MEM_WRITE:   u32 = 0x8000_0000;
+
+// IMAGE_FILE_MACHINE_* constants from the COFF header.
+const IMAGE_FILE_MACHINE_I386:  u16 = 0x014c;
+const IMAGE_FILE_MACHINE_AMD64: u16 = 0x8664;
+const IMAGE_FILE_MACHINE_ARM:   u16 = 0x01c0;
+const IMAGE_FILE_MACHINE_ARM64: u16 = 0xaa64;
+const IMAGE_FILE_MACHINE_R4000: u16 = 0x0166;
+
+
+/// A loaded PE/COFF image.
+#[derive(Clone, Debug)]
+pub struct Pe {
+    base_address: u64,
+    bytes: Vec<u8>,
+    user_function_entries: Vec<u64>
+}
+
+
+impl Pe {
+    /// Create a new `Pe` from the bytes of a PE file, loaded at
+    /// `base_address`.
+    ///
+    /// `base_address` is added on top of the image's own preferred
+    /// `image_base`, mirroring how `Elf::new` lets the caller re-base a
+    /// shared object.
+    pub fn new(bytes: Vec<u8>, base_address: u64) -> Result<Pe> {
+        let peek_bytes: [u8; 16] = clone_into_array(&bytes[0..16]);
+
+        let pe = match goblin::peek_bytes(&peek_bytes)? {
+            Hint::PE => Pe {
+                base_address: base_address,
+                bytes: bytes,
+                user_function_entries: Vec::new()
+            },
+            _ => return Err("Not a valid PE".into())
+        };
+
+        Ok(pe)
+    }
+
+
+    /// Get the base address of this Pe where it has been loaded into loader
+    /// memory.
+    pub fn base_address(&self) -> u64 {
+        self.base_address
+    }
+
+
+    /// Load a Pe from a file and use the given base address.
+    pub fn from_file_with_base_address(filename: &Path, base_address: u64)
+        -> Result<Pe> {
+
+        let mut file = match File::open(filename) {
+            Ok(file) => file,
+            Err(e) => return Err(format!(
+                "Error opening {}: {}",
+                filename.to_str().unwrap(),
+                e).into())
+        };
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        Pe::new(buf, base_address)
+    }
+
+    /// Load a Pe from a file and use the base address of 0.
+    pub fn from_file(filename: &Path) -> Result<Pe> {
+        Pe::from_file_with_base_address(filename, 0)
+    }
+
+    // Allow the user to manually specify a function entry
+    pub fn add_user_function(&mut self, address: u64) {
+        self.user_function_entries.push(address);
+    }
+
+    /// Return the goblin::pe::PE for this Pe.
+    fn pe(&self) -> goblin::pe::PE {
+        goblin::pe::PE::parse(&self.bytes).unwrap()
+    }
+
+    /// The address this image was linked to run at, before `base_address`
+    /// re-basing.
+    fn image_base(&self) -> u64 {
+        self.pe().image_base as u64
+    }
+}
+
+
+impl Loader for Pe {
+    fn memory(&self) -> Result<Memory> {
+        let pe = self.pe();
+        let mut memory = Memory::new();
+
+        let image_base = self.image_base() + self.base_address;
+
+        for section in &pe.sections {
+            let virtual_size = section.virtual_size as usize;
+            let raw_size = section.size_of_raw_data as usize;
+            let file_start = section.pointer_to_raw_data as usize;
+            let file_end = file_start + raw_size;
+
+            let mut bytes = self.bytes
+                                .get(file_start..file_end)
+                                .ok_or("Malformed Pe")?
+                                .to_vec();
+
+            if bytes.len() < virtual_size {
+                bytes.append(&mut vec![0; virtual_size - bytes.len()]);
+            }
+
+            let mut permissions = NONE;
+            if section.characteristics & IMAGE_SCN_MEM_READ != 0 {
+                permissions |= READ;
+            }
+            if section.characteristics & IMAGE_SCN_MEM_WRITE != 0 {
+                permissions |= WRITE;
+            }
+            if section.characteristics & IMAGE_SCN_MEM_EXECUTE != 0 {
+                permissions |= EXECUTE;
+            }
+
+            let segment = MemorySegment::new(
+                image_base + section.virtual_address as u64,
+                bytes,
+                permissions
+            );
+
+            memory.add_segment(segment);
+        }
+
+        Ok(memory)
+    }
+
+
+    fn function_entries(&self) -> Result<Vec<FunctionEntry>> {
+        let pe = self.pe();
+
+        let mut function_entries = Vec::new();
+
+        let image_base = self.image_base() + self.base_address;
+
+        for export in &pe.exports {
+            function_entries.push(FunctionEntry::new(
+                image_base + export.rva as u64,
+                export.name.map(|name| name.to_string())
+            ));
+        }
+
+        for user_function_entry in &self.user_function_entries {
+            function_entries.push(FunctionEntry::new(
+                user_function_entry + self.base_address,
+                Some(format!("user_function_{:x}", user_function_entry))
+            ));
+        }
+
+        Ok(function_entries)
+    }
+
+
+    fn program_entry(&self) -> u64 {
+        let pe = self.pe();
+        self.image_base()
+            + self.base_address
+            + pe.entry as u64
+    }
+
+
+    fn architecture(&self) -> Result<Architecture> {
+        let pe = self.pe();
+
+        match pe.header.coff_header.machine {
+            IMAGE_FILE_MACHINE_I386  => Ok(Architecture::X86),
+            IMAGE_FILE_MACHINE_AMD64 => Ok(Architecture::X8664),
+            IMAGE_FILE_MACHINE_ARM   => Ok(Architecture::Arm),
+            IMAGE_FILE_MACHINE_ARM64 => Ok(Architecture::Arm64),
+            IMAGE_FILE_MACHINE_R4000 => Ok(Architecture::Mips),
+            _ => Err("Unsupported Architecture".into())
+        }
+    }
+}